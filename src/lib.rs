@@ -1,5 +1,6 @@
 use pyo3::prelude::*;
 use pyo3::types::{PyDict, PyList,PyTuple};
+use rayon::prelude::*;
 use std::collections::HashMap;
 use std::f64::consts::PI;
 
@@ -52,14 +53,44 @@ fn get_unique_families_rust(py: Python, hkls: Vec<Vec<i32>>) -> PyResult<PyObjec
     Ok(result.into())
 }
 
+/// Scattering factor parametrization selector
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ScatteringModel {
+    PymatgenStyle,
+    WaasmaierKirfel,
+}
+
+impl ScatteringModel {
+    fn from_str(name: &str) -> PyResult<Self> {
+        match name {
+            "pymatgen" => Ok(ScatteringModel::PymatgenStyle),
+            "waasmaier_kirfel" => Ok(ScatteringModel::WaasmaierKirfel),
+            other => Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "unknown scattering model '{other}', expected 'pymatgen' or 'waasmaier_kirfel'"
+            ))),
+        }
+    }
+}
+
 /// Calculate atomic scattering factor
 #[inline]
-fn calculate_scattering_factor(z: i32, s_squared: f64, coeffs: &[[f64; 2]]) -> f64 {
+fn calculate_scattering_factor(
+    z: i32,
+    s_squared: f64,
+    coeffs: &[[f64; 2]],
+    const_term: f64,
+    model: ScatteringModel,
+) -> f64 {
+    // coeffs holds the per-atom (aᵢ, bᵢ) pairs (five for WaasmaierKirfel);
+    // const_term is the Waasmaier-Kirfel constant c (unused for PymatgenStyle).
     let mut sum = 0.0;
     for coeff in coeffs {
         sum += coeff[0] * (-coeff[1] * s_squared).exp();
     }
-    z as f64 - 41.78214 * s_squared * sum
+    match model {
+        ScatteringModel::PymatgenStyle => z as f64 - 41.78214 * s_squared * sum,
+        ScatteringModel::WaasmaierKirfel => const_term + sum,
+    }
 }
 
 /// Calculate structure factor for a given HKL
@@ -71,28 +102,34 @@ fn calculate_structure_factor(
     scattering_factors: &[f64],
     occupancies: &[f64],
     dw_corrections: &[f64],
+    // per-atom anomalous dispersion corrections f' and f'': effective scattering
+    // factor is (f0 + f') + i*f'', multiplied through by exp(2πi·g·r)
+    f_primes: &[f64],
+    f_double_primes: &[f64],
 ) -> (f64, f64) {
     let mut real_part = 0.0;
     let mut imag_part = 0.0;
-    
+
     for i in 0..frac_coords.len() {
         // Calculate g·r (dot product)
-        let g_dot_r = hkl[0] * frac_coords[i][0] 
-                    + hkl[1] * frac_coords[i][1] 
+        let g_dot_r = hkl[0] * frac_coords[i][0]
+                    + hkl[1] * frac_coords[i][1]
                     + hkl[2] * frac_coords[i][2];
-        
+
         // Calculate exp(2πi·g·r) = cos(2πg·r) + i·sin(2πg·r)
         let angle = 2.0 * PI * g_dot_r;
         let cos_val = angle.cos();
         let sin_val = angle.sin();
-        
-        // Multiply by scattering factor, occupancy, and Debye-Waller correction
-        let factor = scattering_factors[i] * occupancies[i] * dw_corrections[i];
-        
-        real_part += factor * cos_val;
-        imag_part += factor * sin_val;
+
+        // Multiply by scattering factor (plus anomalous dispersion), occupancy, and Debye-Waller correction
+        let occ_dw = occupancies[i] * dw_corrections[i];
+        let factor = (scattering_factors[i] + f_primes[i]) * occ_dw;
+        let f_double_prime_term = f_double_primes[i] * occ_dw;
+
+        real_part += factor * cos_val - f_double_prime_term * sin_val;
+        imag_part += factor * sin_val + f_double_prime_term * cos_val;
     }
-    
+
     (real_part, imag_part)
 }
 
@@ -107,28 +144,53 @@ fn calculate_xrd_intensities(
     scattering_coeffs: Vec<Vec<Vec<f64>>>,
     occupancies: Vec<f64>,
     dw_factors: Vec<f64>,
-) -> PyResult<Vec<(f64, f64)>> {
-    let mut results = Vec::with_capacity(hkls.len());
-    
-    for (idx, hkl) in hkls.iter().enumerate() {
+    scattering_model: Option<String>,
+    const_terms: Option<Vec<f64>>,
+    f_primes: Option<Vec<f64>>,
+    f_double_primes: Option<Vec<f64>>,
+    intensity_tol: Option<f64>,
+    num_threads: Option<usize>,
+) -> PyResult<(Vec<usize>, Vec<(f64, f64)>)> {
+    let intensity_tol = intensity_tol.unwrap_or(0.0);
+    let model = ScatteringModel::from_str(scattering_model.as_deref().unwrap_or("pymatgen"))?;
+    let const_terms = const_terms.unwrap_or_else(|| vec![0.0; atomic_numbers.len()]);
+    if const_terms.len() != atomic_numbers.len() {
+        return Err(pyo3::exceptions::PyValueError::new_err(
+            "const_terms must have one entry per atom",
+        ));
+    }
+    if model == ScatteringModel::WaasmaierKirfel
+        && scattering_coeffs.iter().any(|c| c.len() != 5)
+    {
+        return Err(pyo3::exceptions::PyValueError::new_err(
+            "waasmaier_kirfel requires exactly 5 (a, b) coefficient pairs per atom",
+        ));
+    }
+    let f_primes = f_primes.unwrap_or_else(|| vec![0.0; atomic_numbers.len()]);
+    let f_double_primes = f_double_primes.unwrap_or_else(|| vec![0.0; atomic_numbers.len()]);
+    if f_primes.len() != atomic_numbers.len() || f_double_primes.len() != atomic_numbers.len() {
+        return Err(pyo3::exceptions::PyValueError::new_err(
+            "f_primes and f_double_primes must have one entry per atom",
+        ));
+    }
+
+    let compute_reflection = |idx: usize, hkl: &[f64]| -> (f64, f64) {
         let g_hkl = g_hkls[idx];
-        
+
         if g_hkl == 0.0 {
-            results.push((0.0, 0.0));
-            continue;
+            return (0.0, 0.0);
         }
-        
+
         // Calculate Bragg angle
         let sin_theta = wavelength * g_hkl / 2.0;
         if sin_theta > 1.0 {
-            results.push((0.0, 0.0));
-            continue;
+            return (0.0, 0.0);
         }
-        
+
         let theta = sin_theta.asin();
         let s = g_hkl / 2.0;
         let s_squared = s * s;
-        
+
         // Calculate atomic scattering factors for all atoms
         let mut scattering_factors = Vec::with_capacity(atomic_numbers.len());
         for i in 0..atomic_numbers.len() {
@@ -136,16 +198,22 @@ fn calculate_xrd_intensities(
                 .iter()
                 .map(|c| [c[0], c[1]])
                 .collect();
-            let f = calculate_scattering_factor(atomic_numbers[i], s_squared, &coeffs);
+            let f = calculate_scattering_factor(
+                atomic_numbers[i],
+                s_squared,
+                &coeffs,
+                const_terms[i],
+                model,
+            );
             scattering_factors.push(f);
         }
-        
+
         // Calculate Debye-Waller corrections
         let dw_corrections: Vec<f64> = dw_factors
             .iter()
             .map(|&dw| (-dw * s_squared).exp())
             .collect();
-        
+
         // Calculate structure factor
         let (real, imag) = calculate_structure_factor(
             hkl,
@@ -154,24 +222,49 @@ fn calculate_xrd_intensities(
             &scattering_factors,
             &occupancies,
             &dw_corrections,
+            &f_primes,
+            &f_double_primes,
         );
-        
+
         // Calculate intensity
         let intensity = real * real + imag * imag;
-        
+
         // Lorentz polarization factor
         let cos_theta = theta.cos();
         let sin_theta_sq = theta.sin().powi(2);
         let two_theta = 2.0 * theta;
         let lorentz_factor = (1.0 + two_theta.cos().powi(2)) / (sin_theta_sq * cos_theta);
-        
+
         let final_intensity = intensity * lorentz_factor;
         let two_theta_deg = two_theta.to_degrees();
-        
-        results.push((two_theta_deg, final_intensity));
-    }
-    
-    Ok(results)
+
+        (two_theta_deg, final_intensity)
+    };
+
+    // Each reflection's structure-factor sum is independent, so the loop
+    // parallelizes cleanly over hkls; par_iter preserves input order. Survivor
+    // indices are returned alongside the filtered results so callers can
+    // realign against their own hkls/g_hkls/d_hkls arrays once intensity_tol
+    // has dropped entries.
+    let compute_all = || {
+        hkls.par_iter()
+            .enumerate()
+            .map(|(idx, hkl)| (idx, compute_reflection(idx, hkl)))
+            .filter(|&(_, (_, intensity))| intensity >= intensity_tol)
+            .collect::<Vec<(usize, (f64, f64))>>()
+    };
+
+    let survivors = match num_threads {
+        Some(n) => rayon::ThreadPoolBuilder::new()
+            .num_threads(n)
+            .build()
+            .map_err(|e| pyo3::exceptions::PyRuntimeError::new_err(e.to_string()))?
+            .install(compute_all),
+        None => compute_all(),
+    };
+
+    let (indices, results) = survivors.into_iter().unzip();
+    Ok((indices, results))
 }
 
 /// Fast peak merging based on two-theta tolerance
@@ -248,6 +341,206 @@ fn normalize_intensities(intensities: Vec<f64>, max_value: f64) -> PyResult<Vec<
     Ok(normalized)
 }
 
+/// Caglioti instrumental broadening: FWHM² = U·tan²θ + V·tanθ + W (θ in radians, half of two-theta)
+#[inline]
+fn caglioti_fwhm(two_theta_deg: f64, u: f64, v: f64, w: f64) -> f64 {
+    let theta = two_theta_deg.to_radians() / 2.0;
+    let tan_theta = theta.tan();
+    let fwhm_sq = u * tan_theta * tan_theta + v * tan_theta + w;
+    fwhm_sq.max(0.0).sqrt()
+}
+
+/// Pseudo-Voigt profile value at `x` for a peak centered at `center` with the given FWHM and mixing `eta`
+#[inline]
+fn pseudo_voigt(x: f64, center: f64, fwhm: f64, eta: f64) -> f64 {
+    let dx = x - center;
+    let sigma = fwhm / (2.0 * (2.0_f64 * std::f64::consts::LN_2).sqrt());
+    let gamma = fwhm / 2.0;
+
+    let gaussian = (-dx * dx / (2.0 * sigma * sigma)).exp() / (sigma * (2.0 * PI).sqrt());
+    let lorentzian = gamma / (PI * (dx * dx + gamma * gamma));
+
+    eta * lorentzian + (1.0 - eta) * gaussian
+}
+
+/// Convolve merged reflections into a continuous pseudo-Voigt powder pattern
+#[pyfunction]
+fn generate_profile(
+    two_thetas: Vec<f64>,
+    intensities: Vec<f64>,
+    grid_min: f64,
+    grid_max: f64,
+    step: f64,
+    u: f64,
+    v: f64,
+    w: f64,
+    eta: f64,
+) -> PyResult<(Vec<f64>, Vec<f64>)> {
+    let num_points = ((grid_max - grid_min) / step).floor() as usize + 1;
+    let grid: Vec<f64> = (0..num_points)
+        .map(|i| grid_min + i as f64 * step)
+        .collect();
+    let mut profile = vec![0.0; grid.len()];
+
+    for (&two_theta, &intensity) in two_thetas.iter().zip(intensities.iter()) {
+        if intensity == 0.0 {
+            continue;
+        }
+
+        let fwhm = caglioti_fwhm(two_theta, u, v, w);
+        if fwhm <= 0.0 {
+            continue;
+        }
+
+        // Only evaluate within ±6·FWHM of the center for speed.
+        let window = 6.0 * fwhm;
+        let lo = two_theta - window;
+        let hi = two_theta + window;
+
+        if hi < grid_min || lo > grid_max {
+            continue;
+        }
+
+        let start_idx = ((lo - grid_min) / step).ceil().max(0.0) as usize;
+        let end_idx = (((hi - grid_min) / step).floor() as usize).min(grid.len() - 1);
+
+        for idx in start_idx..=end_idx {
+            profile[idx] += intensity * pseudo_voigt(grid[idx], two_theta, fwhm, eta);
+        }
+    }
+
+    Ok((grid, profile))
+}
+
+/// Enumerate reciprocal lattice points within a limiting sphere
+#[pyfunction]
+fn get_points_in_sphere(
+    recip_matrix: Vec<Vec<f64>>,
+    max_r: f64,
+) -> PyResult<(Vec<Vec<i32>>, Vec<f64>, Vec<f64>)> {
+    let mut hkls = Vec::new();
+    let mut g_hkls = Vec::new();
+    let mut d_hkls = Vec::new();
+
+    if max_r <= 0.0 {
+        return Ok((hkls, g_hkls, d_hkls));
+    }
+
+    let cross = |a: &[f64], b: &[f64]| -> [f64; 3] {
+        [
+            a[1] * b[2] - a[2] * b[1],
+            a[2] * b[0] - a[0] * b[2],
+            a[0] * b[1] - a[1] * b[0],
+        ]
+    };
+    let dot = |a: &[f64], b: &[f64]| -> f64 { a[0] * b[0] + a[1] * b[1] + a[2] * b[2] };
+    let norm = |a: &[f64]| -> f64 { dot(a, a).sqrt() };
+
+    // b1, b2, b3 need not be mutually orthogonal (triclinic cells in
+    // particular aren't), so bounding h by max_r/|b1| is unsound: two axes
+    // can partially cancel and let |h| run larger than that while |G| stays
+    // inside the sphere. Instead bound each index via the dual real-space
+    // vectors a_i (a_i·b_j = δ_ij), for which |h| = |G·a1| ≤ max_r·|a1|.
+    let b1 = &recip_matrix[0];
+    let b2 = &recip_matrix[1];
+    let b3 = &recip_matrix[2];
+    let volume = dot(b1, &cross(b2, b3));
+
+    let bound = |a: [f64; 3]| -> i32 {
+        if volume == 0.0 {
+            0
+        } else {
+            (max_r * norm(&a)).ceil() as i32 + 1
+        }
+    };
+
+    let a1 = cross(b2, b3).map(|x| x / volume);
+    let a2 = cross(b3, b1).map(|x| x / volume);
+    let a3 = cross(b1, b2).map(|x| x / volume);
+
+    let h_max = bound(a1);
+    let k_max = bound(a2);
+    let l_max = bound(a3);
+
+    for h in -h_max..=h_max {
+        for k in -k_max..=k_max {
+            for l in -l_max..=l_max {
+                if h == 0 && k == 0 && l == 0 {
+                    continue;
+                }
+
+                let gx = h as f64 * recip_matrix[0][0]
+                    + k as f64 * recip_matrix[1][0]
+                    + l as f64 * recip_matrix[2][0];
+                let gy = h as f64 * recip_matrix[0][1]
+                    + k as f64 * recip_matrix[1][1]
+                    + l as f64 * recip_matrix[2][1];
+                let gz = h as f64 * recip_matrix[0][2]
+                    + k as f64 * recip_matrix[1][2]
+                    + l as f64 * recip_matrix[2][2];
+
+                let g_mag = (gx * gx + gy * gy + gz * gz).sqrt();
+                if g_mag <= max_r {
+                    hkls.push(vec![h, k, l]);
+                    g_hkls.push(g_mag);
+                    d_hkls.push(if g_mag > 0.0 { 1.0 / g_mag } else { 0.0 });
+                }
+            }
+        }
+    }
+
+    Ok((hkls, g_hkls, d_hkls))
+}
+
+/// Relrod shape factor `(sin(π·s_g·t)/(π·s_g·t))²`, with the limit 1 as s_g→0
+#[inline]
+fn relrod_shape_factor(s_g: f64, thickness: f64) -> f64 {
+    let x = PI * s_g * thickness;
+    if x == 0.0 {
+        1.0
+    } else {
+        (x.sin() / x).powi(2)
+    }
+}
+
+/// Electron-diffraction intensities via Ewald-sphere excitation error and a relrod shape factor
+#[pyfunction]
+fn calculate_ed_intensities(
+    g_cart: Vec<[f64; 3]>,
+    kinematic_intensities: Vec<f64>,
+    wavelength: f64,
+    thickness: f64,
+    max_excitation_error: f64,
+) -> PyResult<(Vec<usize>, Vec<[f64; 3]>, Vec<f64>)> {
+    // r_sphere = 1/λ is the Ewald sphere radius; a spot's distance from the
+    // sphere surface (its excitation error s_g) is |z_sphere - gz|.
+    let r_sphere = 1.0 / wavelength;
+
+    let mut indices = Vec::new();
+    let mut positions = Vec::new();
+    let mut intensities = Vec::new();
+
+    for (idx, (g, &intensity)) in g_cart.iter().zip(kinematic_intensities.iter()).enumerate() {
+        let [gx, gy, gz] = *g;
+        let r_spot_sq = gx * gx + gy * gy;
+        if r_spot_sq > r_sphere * r_sphere {
+            continue;
+        }
+
+        let z_sphere = r_sphere - (r_sphere * r_sphere - r_spot_sq).sqrt();
+        let s_g = (z_sphere - gz).abs();
+
+        if s_g < max_excitation_error {
+            let modulated = intensity * relrod_shape_factor(s_g, thickness);
+            indices.push(idx);
+            positions.push([gx, gy, gz]);
+            intensities.push(modulated);
+        }
+    }
+
+    Ok((indices, positions, intensities))
+}
+
 /// Python module definition
 #[pymodule]
 fn xrd_rust_accelerator(_py: Python, m: &PyModule) -> PyResult<()> {
@@ -255,5 +548,121 @@ fn xrd_rust_accelerator(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(calculate_xrd_intensities, m)?)?;
     m.add_function(wrap_pyfunction!(merge_peaks, m)?)?;
     m.add_function(wrap_pyfunction!(normalize_intensities, m)?)?;
+    m.add_function(wrap_pyfunction!(generate_profile, m)?)?;
+    m.add_function(wrap_pyfunction!(get_points_in_sphere, m)?)?;
+    m.add_function(wrap_pyfunction!(calculate_ed_intensities, m)?)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generate_profile_integrates_to_input_intensity() {
+        let (_, profile) =
+            generate_profile(vec![10.0], vec![100.0], 5.0, 15.0, 0.001, 0.0, 0.0, 0.01, 0.0)
+                .unwrap();
+        let area: f64 = profile.iter().sum::<f64>() * 0.001;
+        assert!((area - 100.0).abs() < 1.0, "area was {area}");
+    }
+
+    #[test]
+    fn generate_profile_skips_peak_entirely_left_of_grid() {
+        let (_, profile) =
+            generate_profile(vec![5.0], vec![100.0], 20.0, 30.0, 0.1, 0.0, 0.0, 0.01, 0.0)
+                .unwrap();
+        assert!(profile.iter().all(|&v| v == 0.0));
+    }
+
+    #[test]
+    fn get_points_in_sphere_matches_brute_force_for_skewed_lattice() {
+        // Non-orthogonal: b2 has a component along b1, so h and k can
+        // partially cancel and run larger than max_r/|b_i| alone would allow.
+        let recip_matrix = vec![
+            vec![1.0, 0.0, 0.0],
+            vec![0.6, 0.8, 0.0],
+            vec![0.0, 0.0, 1.0],
+        ];
+        let max_r = 1.5;
+        let (hkls, g_hkls, d_hkls) = get_points_in_sphere(recip_matrix.clone(), max_r).unwrap();
+
+        let mut expected = 0;
+        for h in -5..=5 {
+            for k in -5..=5 {
+                for l in -5..=5 {
+                    if h == 0 && k == 0 && l == 0 {
+                        continue;
+                    }
+                    let gx = h as f64 * recip_matrix[0][0]
+                        + k as f64 * recip_matrix[1][0]
+                        + l as f64 * recip_matrix[2][0];
+                    let gy = h as f64 * recip_matrix[0][1]
+                        + k as f64 * recip_matrix[1][1]
+                        + l as f64 * recip_matrix[2][1];
+                    let gz = h as f64 * recip_matrix[0][2]
+                        + k as f64 * recip_matrix[1][2]
+                        + l as f64 * recip_matrix[2][2];
+                    if (gx * gx + gy * gy + gz * gz).sqrt() <= max_r {
+                        expected += 1;
+                    }
+                }
+            }
+        }
+
+        assert_eq!(hkls.len(), expected);
+        assert_eq!(hkls.len(), g_hkls.len());
+        assert_eq!(hkls.len(), d_hkls.len());
+    }
+
+    #[test]
+    fn get_points_in_sphere_excludes_origin() {
+        let recip_matrix = vec![
+            vec![1.0, 0.0, 0.0],
+            vec![0.0, 1.0, 0.0],
+            vec![0.0, 0.0, 1.0],
+        ];
+        let (hkls, _, _) = get_points_in_sphere(recip_matrix, 1.1).unwrap();
+        assert!(!hkls.contains(&vec![0, 0, 0]));
+        assert_eq!(hkls.len(), 6); // the six unit-length axis directions
+    }
+
+    #[test]
+    fn calculate_ed_intensities_keeps_on_sphere_spot_unmodulated() {
+        let wavelength = 0.02;
+        let r_sphere = 1.0 / wavelength;
+        let (gx, gy): (f64, f64) = (0.1, 0.0);
+        let z_sphere = r_sphere - (r_sphere * r_sphere - gx * gx - gy * gy).sqrt();
+
+        let (indices, _, intensities) = calculate_ed_intensities(
+            vec![[gx, gy, z_sphere]],
+            vec![50.0],
+            wavelength,
+            100.0,
+            0.01,
+        )
+        .unwrap();
+
+        assert_eq!(indices, vec![0]);
+        assert!((intensities[0] - 50.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn calculate_ed_intensities_drops_spot_beyond_excitation_error() {
+        let wavelength = 0.02;
+        let r_sphere = 1.0 / wavelength;
+        let (gx, gy): (f64, f64) = (0.1, 0.0);
+        let z_sphere = r_sphere - (r_sphere * r_sphere - gx * gx - gy * gy).sqrt();
+
+        let (indices, _, _) = calculate_ed_intensities(
+            vec![[gx, gy, z_sphere + 1.0]],
+            vec![50.0],
+            wavelength,
+            100.0,
+            0.01,
+        )
+        .unwrap();
+
+        assert!(indices.is_empty());
+    }
+}